@@ -1,5 +1,56 @@
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
 use std::str::from_utf8;
-use std::path::PathBuf;
+
+use crate::{ArrayMerge, ConfigParseError};
+
+/// Recursion depth used when merging layered `languages.toml` files, unless
+/// a layer overrides it with a top-level `config-merge-depth` key.
+const DEFAULT_LANG_CONFIG_MERGE_DEPTH: usize = 3;
+
+/// `language-server`/`formatter`/`debugger` keys that let a `languages.toml`
+/// launch an external process, stripped from an untrusted workspace layer
+/// by [`filter_untrusted_language_config`].
+const UNTRUSTED_LANGUAGE_DENYLIST: &[&str] = &["command"];
+
+/// Strips [`UNTRUSTED_LANGUAGE_DENYLIST`] keys from an untrusted workspace
+/// `languages.toml`'s `language-server` table and each language's
+/// `formatter`/`debugger` table, mirroring `filter_untrusted_editor` in
+/// `helix-term`'s config loader.
+fn filter_untrusted_language_config(mut value: toml::Value) -> toml::Value {
+    if let Some(servers) = value
+        .get_mut("language-server")
+        .and_then(toml::Value::as_table_mut)
+    {
+        for server in servers.values_mut() {
+            if let Some(table) = server.as_table_mut() {
+                for key in UNTRUSTED_LANGUAGE_DENYLIST {
+                    table.remove(*key);
+                }
+            }
+        }
+    }
+
+    if let Some(languages) = value
+        .get_mut("language")
+        .and_then(toml::Value::as_array_mut)
+    {
+        for language in languages {
+            let Some(table) = language.as_table_mut() else {
+                continue;
+            };
+            for section in ["formatter", "debugger"] {
+                if let Some(sub) = table.get_mut(section).and_then(toml::Value::as_table_mut) {
+                    for key in UNTRUSTED_LANGUAGE_DENYLIST {
+                        sub.remove(*key);
+                    }
+                }
+            }
+        }
+    }
+
+    value
+}
 
 /// Default built-in languages.toml.
 pub fn default_lang_config() -> toml::Value {
@@ -8,28 +59,180 @@ pub fn default_lang_config() -> toml::Value {
         .expect("Could not parse built-in languages.toml to valid toml")
 }
 
+/// Reads `file` as TOML and resolves its `include = [...]` directive, if
+/// any (see [`crate::resolve_includes`] for the details, including the
+/// cycle/depth/absolute-path guards). Returns `None` if `file` doesn't
+/// exist, matching the leniency of the rest of the languages-config loader.
+fn load_toml_with_includes(file: &Path) -> Result<Option<toml::Value>, ConfigParseError> {
+    if !file.exists() {
+        return Ok(None);
+    }
+    let mut visited = HashSet::new();
+    crate::resolve_includes(file, &mut visited, 0, &|_| {
+        (DEFAULT_LANG_CONFIG_MERGE_DEPTH, ArrayMerge::Append)
+    })
+    .map(Some)
+}
+
 fn merge_language_config(
-    left: toml::Value, file: PathBuf,
-) -> Result<toml::Value, toml::de::Error> {
-    let right = std::fs::read_to_string(file).ok()
-        .map(|c| toml::from_str(&c)).transpose()?;
+    left: toml::Value,
+    file: PathBuf,
+    trust: bool,
+) -> Result<toml::Value, ConfigParseError> {
+    let right = load_toml_with_includes(&file)?;
 
     let config = match right {
-        Some(right) => crate::merge_toml_values(left, right, 3),
+        Some(right) => {
+            let right = if trust {
+                right
+            } else {
+                filter_untrusted_language_config(right)
+            };
+            // Only a trusted layer gets to pick its own merge depth: an
+            // untrusted layer controlling this could force a wholesale
+            // table replacement (depth 0) and wipe out the trusted
+            // defaults this merge is supposed to only extend.
+            let merge_depth = if trust {
+                right.get("config-merge-depth")
+                    .and_then(toml::Value::as_integer)
+                    .and_then(|depth| usize::try_from(depth).ok())
+                    .unwrap_or(DEFAULT_LANG_CONFIG_MERGE_DEPTH)
+            } else {
+                DEFAULT_LANG_CONFIG_MERGE_DEPTH
+            };
+            crate::merge_toml_values(left, right, merge_depth, ArrayMerge::Append)
+        }
         None => left,
     };
 
     Ok(config)
 }
 
-/// User configured languages.toml file, merged with the default config.
-pub fn user_lang_config() -> Result<toml::Value, toml::de::Error> {
-    let global = merge_language_config(default_lang_config(), crate::lang_config_file())?;
+/// User configured languages.toml file, merged with the default config. The
+/// workspace layer is only merged in as fully trusted if its workspace has
+/// been trusted via [`crate::is_workspace_trusted`]; otherwise keys that
+/// launch an external process (`language-server.*.command`, and each
+/// language's `formatter`/`debugger` command) are stripped first, the same
+/// restriction `helix-term`'s config loader applies to `editor`. On a parse
+/// error, the returned [`ConfigParseError`] names the offending file (the
+/// global or workspace `languages.toml`) and, where available, the 1-based
+/// line/column.
+pub fn user_lang_config() -> Result<toml::Value, ConfigParseError> {
+    let global = merge_language_config(default_lang_config(), crate::lang_config_file(), true)?;
 
     let config = match global.get("workspace-config").and_then(|v| v.as_bool()) {
-        Some(true) => merge_language_config(global, crate::workspace_lang_config_file())?,
+        Some(true) => {
+            let trusted = crate::is_workspace_trusted(&crate::find_workspace());
+            merge_language_config(global, crate::workspace_lang_config_file(), trusted)?
+        }
         _ => global,
     };
 
-    Ok(config)
+    // Expand `${VAR}`/`~` in language server/formatter `command` paths etc.
+    // so configs can stay portable across machines.
+    Ok(crate::expand_config_value(config))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "helix-loader-test-{name}-{}",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn filter_untrusted_language_config_strips_commands() {
+        let value: toml::Value = toml::from_str(
+            r#"
+            [language-server.evil]
+            command = "rm"
+            args = ["-rf", "/"]
+
+            [[language]]
+            name = "rust"
+            [language.formatter]
+            command = "rustfmt"
+            "#,
+        )
+        .unwrap();
+
+        let filtered = filter_untrusted_language_config(value);
+
+        let server = filtered
+            .get("language-server")
+            .and_then(|v| v.get("evil"))
+            .unwrap();
+        assert!(server.get("command").is_none());
+        assert!(server.get("args").is_some());
+
+        let formatter = filtered
+            .get("language")
+            .and_then(|v| v.as_array())
+            .and_then(|languages| languages.first())
+            .and_then(|language| language.get("formatter"))
+            .unwrap();
+        assert!(formatter.get("command").is_none());
+    }
+
+    #[test]
+    fn merge_language_config_ignores_untrusted_config_merge_depth() {
+        let dir = temp_dir("config-merge-depth");
+        std::fs::write(
+            dir.join("languages.toml"),
+            "config-merge-depth = 0\n\n[[language]]\nname = \"rust\"\n",
+        )
+        .unwrap();
+
+        let left: toml::Value = toml::from_str(
+            "[[language]]\nname = \"toml\"\n",
+        )
+        .unwrap();
+
+        // An untrusted `languages.toml` setting `config-merge-depth = 0`
+        // must not be able to force its own table to replace `left`
+        // outright: the `toml` language entry belongs to `left` and must
+        // survive the merge.
+        let merged = merge_language_config(left, dir.join("languages.toml"), false).unwrap();
+        let languages = merged.get("language").and_then(toml::Value::as_array).unwrap();
+        let names: Vec<&str> = languages
+            .iter()
+            .filter_map(|language| language.get("name").and_then(toml::Value::as_str))
+            .collect();
+        assert!(names.contains(&"toml"));
+        assert!(names.contains(&"rust"));
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn resolve_includes_detects_cycles() {
+        let dir = temp_dir("include-cycle");
+        std::fs::write(dir.join("a.toml"), "include = [\"b.toml\"]\n").unwrap();
+        std::fs::write(dir.join("b.toml"), "include = [\"a.toml\"]\n").unwrap();
+
+        let error = load_toml_with_includes(&dir.join("a.toml"))
+            .expect_err("an include cycle should error instead of overflowing the stack");
+        assert!(error.message.contains("cycle"));
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn resolve_includes_reports_missing_target() {
+        let dir = temp_dir("include-missing");
+        std::fs::write(dir.join("a.toml"), "include = [\"missing.toml\"]\n").unwrap();
+
+        let error = load_toml_with_includes(&dir.join("a.toml"))
+            .expect_err("a missing include target should name the file that was missing");
+        assert!(error.path.ends_with("missing.toml"));
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
 }
@@ -0,0 +1,570 @@
+pub mod config;
+
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+
+/// A TOML parse error enriched with the file it came from and, when the
+/// underlying parser exposes one, the 1-based line/column the error
+/// occurred at. Lets callers layering several config files (global,
+/// workspace, `languages.toml`) report exactly which one was bad.
+#[derive(Debug)]
+pub struct ConfigParseError {
+    pub path: PathBuf,
+    pub line_col: Option<(usize, usize)>,
+    pub message: String,
+}
+
+impl ConfigParseError {
+    pub fn new(path: PathBuf, source: &str, error: toml::de::Error) -> Self {
+        let line_col = error.span().map(|span| offset_to_line_col(source, span.start));
+        Self {
+            path,
+            line_col,
+            message: error.message().to_string(),
+        }
+    }
+
+    /// Like [`ConfigParseError::new`], for errors raised converting an
+    /// already-parsed `toml::Value` (e.g. after merging config layers)
+    /// rather than directly from a file's source text, so no line/column
+    /// is attempted.
+    pub fn without_source(path: PathBuf, error: toml::de::Error) -> Self {
+        Self {
+            path,
+            line_col: None,
+            message: error.message().to_string(),
+        }
+    }
+
+    /// Wraps an IO error encountered while reading `path` (e.g. a missing
+    /// `include` target) so the failure names the file that was being read,
+    /// instead of a bare "No such file or directory".
+    pub fn io(path: PathBuf, error: std::io::Error) -> Self {
+        Self {
+            path,
+            line_col: None,
+            message: error.to_string(),
+        }
+    }
+
+    /// A hand-written message not derived from a `toml::de::Error` (e.g. an
+    /// `include` cycle or max-depth error).
+    pub fn message(path: PathBuf, message: String) -> Self {
+        Self {
+            path,
+            line_col: None,
+            message,
+        }
+    }
+}
+
+impl std::fmt::Display for ConfigParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.line_col {
+            Some((line, col)) => write!(f, "{}:{}:{}: {}", self.path.display(), line, col, self.message),
+            None => write!(f, "{}: {}", self.path.display(), self.message),
+        }
+    }
+}
+
+fn offset_to_line_col(source: &str, offset: usize) -> (usize, usize) {
+    let offset = offset.min(source.len());
+    let mut line = 1;
+    let mut col = 1;
+    for ch in source[..offset].chars() {
+        if ch == '\n' {
+            line += 1;
+            col = 1;
+        } else {
+            col += 1;
+        }
+    }
+    (line, col)
+}
+
+/// Directory holding the user's global `config.toml`/`languages.toml`,
+/// honouring `$HELIX_CONFIG_HOME` for overrides.
+pub fn config_dir() -> PathBuf {
+    if let Some(dir) = std::env::var_os("HELIX_CONFIG_HOME") {
+        return PathBuf::from(dir);
+    }
+    let base = std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| home_dir().map(|home| home.join(".config")))
+        .unwrap_or_else(|| PathBuf::from("."));
+    base.join("helix")
+}
+
+/// Directory Helix stores local runtime state in, such as trust decisions.
+pub fn state_dir() -> PathBuf {
+    if let Some(dir) = std::env::var_os("HELIX_STATE_HOME") {
+        return PathBuf::from(dir);
+    }
+    let base = std::env::var_os("XDG_STATE_HOME")
+        .map(PathBuf::from)
+        .or_else(|| home_dir().map(|home| home.join(".local").join("state")))
+        .unwrap_or_else(|| PathBuf::from("."));
+    base.join("helix")
+}
+
+pub fn config_file() -> PathBuf {
+    config_dir().join("config.toml")
+}
+
+pub fn lang_config_file() -> PathBuf {
+    config_dir().join("languages.toml")
+}
+
+/// Walks upward from the current directory and returns the nearest one
+/// containing a `.helix` folder or a `.git` entry (whichever is found
+/// first), falling back to the current directory if neither is found.
+pub fn find_workspace() -> PathBuf {
+    let cwd = std::env::current_dir().unwrap_or_default();
+    for dir in cwd.ancestors() {
+        if dir.join(".helix").is_dir() || dir.join(".git").exists() {
+            return dir.to_path_buf();
+        }
+    }
+    cwd
+}
+
+pub fn workspace_config_file() -> PathBuf {
+    find_workspace().join(".helix").join("config.toml")
+}
+
+pub fn workspace_lang_config_file() -> PathBuf {
+    find_workspace().join(".helix").join("languages.toml")
+}
+
+/// File listing the workspaces the user has explicitly trusted with a
+/// `.helix/config.toml`/`.helix/languages.toml`, one hash per line.
+fn trust_store_file() -> PathBuf {
+    state_dir().join("trusted_workspaces")
+}
+
+/// A short, stable (but non-cryptographic) identifier for a workspace path,
+/// used as its entry in the trust store. This isn't a security boundary by
+/// itself, just a filesystem-safe key.
+fn workspace_id(workspace: &Path) -> String {
+    use std::collections::hash_map::DefaultHasher;
+    use std::hash::{Hash, Hasher};
+
+    let mut hasher = DefaultHasher::new();
+    workspace.hash(&mut hasher);
+    format!("{:016x}", hasher.finish())
+}
+
+/// Whether `workspace` has previously been trusted via [`trust_workspace`].
+pub fn is_workspace_trusted(workspace: &Path) -> bool {
+    let id = workspace_id(workspace);
+    std::fs::read_to_string(trust_store_file())
+        .map(|contents| contents.lines().any(|line| line == id))
+        .unwrap_or(false)
+}
+
+/// Remembers that the user has trusted `workspace`'s `.helix/config.toml`,
+/// so future loads don't need to ask again.
+pub fn trust_workspace(workspace: &Path) -> std::io::Result<()> {
+    if is_workspace_trusted(workspace) {
+        return Ok(());
+    }
+
+    let file = trust_store_file();
+    if let Some(dir) = file.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+
+    use std::io::Write;
+    let mut file = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(file)?;
+    writeln!(file, "{}", workspace_id(workspace))
+}
+
+/// The current user's home directory, used to expand a leading `~` in
+/// config string values. `None` if it can't be determined (e.g. `$HOME`
+/// isn't set on this platform).
+pub fn home_dir() -> Option<std::path::PathBuf> {
+    std::env::var_os("HOME").map(std::path::PathBuf::from)
+}
+
+/// Expands `${VAR}`/`$VAR` environment variable references and a leading
+/// `~` in every string leaf of `value`, recursively. Unknown variables are
+/// left untouched rather than failing the load, since a typo'd or
+/// platform-specific variable shouldn't be fatal for the rest of the
+/// config.
+pub fn expand_config_value(value: toml::Value) -> toml::Value {
+    match value {
+        toml::Value::String(s) => toml::Value::String(expand_string(&s)),
+        toml::Value::Array(items) => {
+            toml::Value::Array(items.into_iter().map(expand_config_value).collect())
+        }
+        toml::Value::Table(table) => toml::Value::Table(
+            table
+                .into_iter()
+                .map(|(key, value)| (key, expand_config_value(value)))
+                .collect(),
+        ),
+        other => other,
+    }
+}
+
+/// Expands environment variable references, then a leading `~`, in a
+/// single string.
+pub fn expand_string(s: &str) -> String {
+    expand_tilde(&expand_env_vars(s))
+}
+
+/// Expands a leading `~` (or `~/...`) into the user's home directory.
+fn expand_tilde(s: &str) -> String {
+    match s.strip_prefix('~') {
+        Some(rest) if rest.is_empty() || rest.starts_with('/') => match home_dir() {
+            Some(home) => format!("{}{}", home.display(), rest),
+            None => s.to_string(),
+        },
+        _ => s.to_string(),
+    }
+}
+
+/// Expands `${VAR}` and `$VAR` references using `std::env::var`, leaving
+/// unset variables in place untouched.
+fn expand_env_vars(s: &str) -> String {
+    let mut result = String::with_capacity(s.len());
+    let mut chars = s.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if c != '$' {
+            result.push(c);
+            continue;
+        }
+
+        if chars.peek() == Some(&'{') {
+            chars.next();
+            let name: String = chars.by_ref().take_while(|&c| c != '}').collect();
+            match std::env::var(&name) {
+                Ok(value) => result.push_str(&value),
+                Err(_) => result.push_str(&format!("${{{name}}}")),
+            }
+            continue;
+        }
+
+        if matches!(chars.peek(), Some(c) if c.is_ascii_alphabetic() || *c == '_') {
+            let mut name = String::new();
+            while matches!(chars.peek(), Some(c) if c.is_ascii_alphanumeric() || *c == '_') {
+                name.push(chars.next().unwrap());
+            }
+            match std::env::var(&name) {
+                Ok(value) => result.push_str(&value),
+                Err(_) => {
+                    result.push('$');
+                    result.push_str(&name);
+                }
+            }
+            continue;
+        }
+
+        result.push('$');
+    }
+
+    result
+}
+
+/// How two TOML arrays should be combined when merging config layers.
+///
+/// `Append` is the historical behaviour: arrays of tables are merged
+/// element-wise by `name`, and anything else is just appended.
+/// `Replace` makes the right-hand layer win outright, which is useful for
+/// list-like settings (e.g. `editor.rulers`) where a workspace config wants
+/// to fully override rather than extend the global list.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ArrayMerge {
+    Append,
+    Replace,
+}
+
+impl Default for ArrayMerge {
+    fn default() -> Self {
+        ArrayMerge::Append
+    }
+}
+
+/// Reserved key used inside a table to override how *arrays nested in that
+/// table* are combined, e.g. `merge = "replace"` makes an array field win
+/// outright instead of being merged by `name`. It does not affect the
+/// table's other keys, which are still merged key-by-key as usual. The key
+/// is removed from each right-hand table that is merged against a left-hand
+/// table; a table with no left-hand counterpart is inserted as it is, and
+/// keeps any `merge` keys it contains.
+const MERGE_STRATEGY_KEY: &str = "merge";
+
+fn merge_strategy(table: &toml::value::Table) -> Option<ArrayMerge> {
+    match table.get(MERGE_STRATEGY_KEY).and_then(toml::Value::as_str) {
+        Some("replace") => Some(ArrayMerge::Replace),
+        Some("deep") | Some("append") => Some(ArrayMerge::Append),
+        _ => None,
+    }
+}
+
+/// Merges two TOML documents, with values in `right` taking precedence.
+///
+/// `merge_depth` controls how many levels of nested tables are merged
+/// key-by-key before the right-hand value simply overrides the left; `0`
+/// means "right always wins". `array_merge` controls how two arrays at the
+/// same path are combined, unless overridden locally via a `merge = ...`
+/// key in the enclosing table (see [`ArrayMerge`]).
+pub fn merge_toml_values(
+    left: toml::Value,
+    right: toml::Value,
+    merge_depth: usize,
+    array_merge: ArrayMerge,
+) -> toml::Value {
+    use toml::Value;
+
+    fn get_name(v: &Value) -> Option<&str> {
+        v.get("name").and_then(Value::as_str)
+    }
+
+    match (left, right) {
+        (Value::Array(left_items), Value::Array(right_items)) => {
+            if merge_depth == 0 || array_merge == ArrayMerge::Replace {
+                Value::Array(right_items)
+            } else {
+                // Only merge the values that share a `name`; anything else
+                // from the right-hand array is appended.
+                let mut left_items = left_items;
+                left_items.reserve(right_items.len());
+                for right in right_items {
+                    let left = get_name(&right)
+                        .and_then(|name| left_items.iter().position(|left| get_name(left) == Some(name)))
+                        .map(|position| left_items.remove(position));
+                    let merged = match left {
+                        Some(left) => merge_toml_values(left, right, merge_depth - 1, array_merge),
+                        None => right,
+                    };
+                    left_items.push(merged);
+                }
+                Value::Array(left_items)
+            }
+        }
+        (Value::Table(mut left_map), Value::Table(mut right_map)) => {
+            let local_array_merge = merge_strategy(&right_map).unwrap_or(array_merge);
+            right_map.remove(MERGE_STRATEGY_KEY);
+
+            if merge_depth == 0 {
+                Value::Table(right_map)
+            } else {
+                // `local_array_merge` only changes how arrays *inside* this
+                // table are combined; keys the right-hand side didn't
+                // redeclare still come through from the left unchanged, the
+                // same as the `ArrayMerge::Append` case.
+                for (key, value) in right_map {
+                    match left_map.remove(&key) {
+                        Some(left_value) => {
+                            let merged = merge_toml_values(
+                                left_value,
+                                value,
+                                merge_depth - 1,
+                                local_array_merge,
+                            );
+                            left_map.insert(key, merged);
+                        }
+                        None => {
+                            left_map.insert(key, value);
+                        }
+                    }
+                }
+                Value::Table(left_map)
+            }
+        }
+        // Otherwise, use the right value, overriding the left.
+        (_, value) => value,
+    }
+}
+
+/// Maximum depth of nested `include` directives before erroring out, so an
+/// accidental or malicious include cycle fails loudly instead of recursing
+/// until the stack overflows.
+pub const MAX_INCLUDE_DEPTH: usize = 16;
+
+/// Reads `file` as TOML and resolves its `include = [...]` directive, if
+/// any: each listed path is resolved relative to `file`'s directory and
+/// merged in as a base layer that `file`'s own values override, recursively
+/// resolving its own includes first. Later entries in `include` override
+/// earlier ones. `merge_settings` computes the merge depth/array-merge
+/// strategy used to fold each layer in, from the already-parsed document
+/// (e.g. honouring a `config-merge-depth` hint).
+///
+/// `file` must already be known to exist; callers that treat a missing
+/// top-level config file as "nothing configured" should check that before
+/// calling this. A missing `include` target, an absolute `include` path (an
+/// include is only meant to pull in files scoped under the including file,
+/// not arbitrary paths the process can read), an include cycle, or nesting
+/// past [`MAX_INCLUDE_DEPTH`] are all errors rather than silently ignored.
+pub fn resolve_includes(
+    file: &Path,
+    visited: &mut HashSet<PathBuf>,
+    depth: usize,
+    merge_settings: &impl Fn(&toml::Value) -> (usize, ArrayMerge),
+) -> Result<toml::Value, ConfigParseError> {
+    if depth > MAX_INCLUDE_DEPTH {
+        return Err(ConfigParseError::message(
+            file.to_path_buf(),
+            format!("`include` nesting exceeds the maximum depth of {MAX_INCLUDE_DEPTH}"),
+        ));
+    }
+
+    let canonical = std::fs::canonicalize(file).unwrap_or_else(|_| file.to_path_buf());
+    if !visited.insert(canonical.clone()) {
+        return Err(ConfigParseError::message(
+            file.to_path_buf(),
+            "`include` cycle detected".to_string(),
+        ));
+    }
+
+    let result = (|| {
+        let source = std::fs::read_to_string(file)
+            .map_err(|error| ConfigParseError::io(file.to_path_buf(), error))?;
+        let mut value: toml::Value = toml::from_str(&source)
+            .map_err(|error| ConfigParseError::new(file.to_path_buf(), &source, error))?;
+
+        let includes = value.as_table_mut().and_then(|table| table.remove("include"));
+        let Some(includes) = includes else {
+            return Ok(value);
+        };
+        let includes = includes.as_array().cloned().ok_or_else(|| {
+            ConfigParseError::message(
+                file.to_path_buf(),
+                "`include` must be an array of paths".to_string(),
+            )
+        })?;
+
+        let (merge_depth, merge_arrays) = merge_settings(&value);
+
+        let dir = file.parent().unwrap_or_else(|| Path::new("."));
+        let mut base = toml::Value::Table(toml::value::Table::new());
+        for include in &includes {
+            let path = include.as_str().ok_or_else(|| {
+                ConfigParseError::message(
+                    file.to_path_buf(),
+                    "`include` entries must be strings".to_string(),
+                )
+            })?;
+            let path = Path::new(path);
+            if path.is_absolute() {
+                return Err(ConfigParseError::message(
+                    file.to_path_buf(),
+                    format!("`include` paths must be relative to the including file, got `{}`", path.display()),
+                ));
+            }
+            let included = resolve_includes(&dir.join(path), visited, depth + 1, merge_settings)?;
+            base = merge_toml_values(base, included, merge_depth, merge_arrays);
+        }
+
+        Ok(merge_toml_values(base, value, merge_depth, merge_arrays))
+    })();
+
+    visited.remove(&canonical);
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn toml(s: &str) -> toml::Value {
+        toml::from_str(s).unwrap()
+    }
+
+    #[test]
+    fn merge_depth_zero_lets_right_win_outright() {
+        let left = toml("[a]\nx = 1\ny = 2\n");
+        let right = toml("[a]\nx = 9\n");
+
+        let merged = merge_toml_values(left, right, 0, ArrayMerge::Append);
+        assert_eq!(merged, toml("[a]\nx = 9\n"));
+    }
+
+    #[test]
+    fn merge_depth_one_merges_only_the_top_level() {
+        let left = toml("[a]\nx = 1\ny = 2\n");
+        let right = toml("[a]\nx = 9\n");
+
+        let merged = merge_toml_values(left, right, 1, ArrayMerge::Append);
+        assert_eq!(merged, toml("[a]\nx = 9\n"));
+    }
+
+    #[test]
+    fn array_merge_replace_drops_the_left_array() {
+        let left = toml("a = [1, 2, 3]\n");
+        let right = toml("a = [4]\n");
+
+        let merged = merge_toml_values(left, right, 3, ArrayMerge::Replace);
+        assert_eq!(merged, toml("a = [4]\n"));
+    }
+
+    #[test]
+    fn array_merge_append_merges_by_name_and_appends_the_rest() {
+        let left = toml(
+            r#"
+            a = [{ name = "one", value = 1 }, { name = "two", value = 2 }]
+            "#,
+        );
+        let right = toml(
+            r#"
+            a = [{ name = "one", value = 9 }, { name = "three", value = 3 }]
+            "#,
+        );
+
+        let merged = merge_toml_values(left, right, 3, ArrayMerge::Append);
+        assert_eq!(
+            merged,
+            toml(
+                r#"
+                a = [
+                    { name = "two", value = 2 },
+                    { name = "one", value = 9 },
+                    { name = "three", value = 3 },
+                ]
+                "#,
+            )
+        );
+    }
+
+    #[test]
+    fn local_merge_replace_hint_overrides_the_array_merge_strategy() {
+        let left = toml("[a]\nx = [1, 2]\ny = 2\n");
+        let right = toml("[a]\nmerge = \"replace\"\nx = [3]\n");
+
+        let merged = merge_toml_values(left, right, 3, ArrayMerge::Append);
+        // `x` is replaced outright per the local hint, but `y` — a sibling
+        // key the right-hand table didn't redeclare — must still come
+        // through from the left, not be dropped along with the rest of the
+        // table.
+        assert_eq!(merged, toml("[a]\nx = [3]\ny = 2\n"));
+    }
+
+    #[test]
+    fn resolve_includes_rejects_absolute_paths() {
+        let dir = std::env::temp_dir().join(format!(
+            "helix-loader-test-include-absolute-{}",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(
+            dir.join("a.toml"),
+            "include = [\"/etc/does-not-matter.toml\"]\n",
+        )
+        .unwrap();
+
+        let error = resolve_includes(&dir.join("a.toml"), &mut HashSet::new(), 0, &|_| {
+            (3, ArrayMerge::Append)
+        })
+        .expect_err("an absolute include path should be rejected");
+        assert!(error.message.contains("relative"));
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
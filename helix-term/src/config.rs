@@ -1,19 +1,17 @@
 use crate::keymap;
 use crate::keymap::{merge_keys, KeyTrie};
-use helix_loader::merge_toml_values;
+use helix_loader::{expand_config_value, expand_string, merge_toml_values, ArrayMerge, ConfigParseError};
 use helix_view::document::Mode;
 use serde::Deserialize;
-use std::collections::HashMap;
-use std::path::PathBuf;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
 use std::fmt::Display;
-use std::fs;
 use std::io::Error as IOError;
-use toml::de::Error as TomlError;
 
 // Config loading error
 #[derive(Debug)]
 pub enum ConfigLoadError {
-    BadConfig(TomlError),
+    BadConfig(ConfigParseError),
     Error(IOError),
 }
 
@@ -24,6 +22,29 @@ impl Default for ConfigLoadError {
 }
 
 
+/// Recursion depth used when merging layered `editor` tables, unless a
+/// layer overrides it with `config-merge-depth`.
+const DEFAULT_CONFIG_MERGE_DEPTH: usize = 3;
+
+/// `editor` keys an untrusted workspace config (see the `trust` parameter
+/// of [`ConfigRaw::merge`]) may not set, because they control what
+/// external commands Helix will run.
+const UNTRUSTED_EDITOR_DENYLIST: &[&str] = &["shell"];
+
+/// Strips [`UNTRUSTED_EDITOR_DENYLIST`] keys from an untrusted workspace's
+/// `editor` table before it's merged in.
+fn filter_untrusted_editor(editor: toml::Value) -> toml::Value {
+    match editor {
+        toml::Value::Table(mut table) => {
+            for key in UNTRUSTED_EDITOR_DENYLIST {
+                table.remove(*key);
+            }
+            toml::Value::Table(table)
+        }
+        other => other,
+    }
+}
+
 // Deserializable raw config struct
 #[derive(Debug, Clone, PartialEq, Deserialize)]
 #[serde(rename_all = "kebab-case", deny_unknown_fields)]
@@ -32,6 +53,15 @@ pub struct ConfigRaw {
     pub theme: Option<String>,
     pub keys: Option<HashMap<Mode, KeyTrie>>,
     pub editor: Option<toml::Value>,
+    /// How many levels of nested `editor` tables are merged key-by-key
+    /// before a later layer simply overrides an earlier one. Defaults to 3.
+    pub config_merge_depth: Option<usize>,
+    /// How arrays inside `editor` (e.g. `editor.rulers`) combine across
+    /// layers: `"append"` (the default) merges language-like arrays by
+    /// `name` and appends the rest, `"replace"` lets the later layer win
+    /// outright. Individual tables can override this locally with a
+    /// `merge = "replace"` key.
+    pub config_merge_arrays: Option<ArrayMerge>,
 }
 
 impl Default for ConfigRaw {
@@ -41,17 +71,157 @@ impl Default for ConfigRaw {
             theme: None,
             keys: Some(keymap::default()),
             editor: None,
+            config_merge_depth: None,
+            config_merge_arrays: None,
+        }
+    }
+}
+
+/// The result of [`ConfigRaw::load_raw`]: the merged global+workspace raw
+/// config, plus enough provenance to tell [`Config::load_lenient`] which
+/// file a given top-level section actually came from.
+struct RawLayers {
+    value: toml::Value,
+    global_path: PathBuf,
+    /// `Some` only if a workspace config was loaded and merged in.
+    workspace_path: Option<PathBuf>,
+    /// Top-level keys the workspace layer set, so a section present there
+    /// can be blamed on `workspace_path` rather than `global_path`.
+    workspace_keys: HashSet<String>,
+}
+
+impl RawLayers {
+    fn global_only(value: toml::Value, global_path: PathBuf) -> Self {
+        Self {
+            value,
+            global_path,
+            workspace_path: None,
+            workspace_keys: HashSet::new(),
+        }
+    }
+
+    /// The file a given top-level `section` should be blamed on: the
+    /// workspace config if it set that section, otherwise the global one.
+    fn path_for(&self, section: &str) -> PathBuf {
+        if self.workspace_keys.contains(section) {
+            self.workspace_path.clone().unwrap_or_else(|| self.global_path.clone())
+        } else {
+            self.global_path.clone()
         }
     }
 }
 
 impl ConfigRaw {
     fn load(file: PathBuf) -> Result<Self, ConfigLoadError> {
-        let source = fs::read_to_string(file).map_err(ConfigLoadError::Error)?;
-        toml::from_str(&source).map_err(ConfigLoadError::BadConfig)
+        Self::load_toml(&file)?
+            .try_into()
+            .map_err(|error| {
+                ConfigLoadError::BadConfig(ConfigParseError::without_source(file, error))
+            })
+    }
+
+    /// Reads the `config-merge-depth`/`config-merge-arrays` hints off a
+    /// raw document, falling back to the defaults when absent or invalid.
+    fn merge_settings(value: &toml::Value) -> (usize, ArrayMerge) {
+        let merge_depth = value.get("config-merge-depth")
+            .and_then(toml::Value::as_integer)
+            .and_then(|depth| usize::try_from(depth).ok())
+            .unwrap_or(DEFAULT_CONFIG_MERGE_DEPTH);
+        let merge_arrays = value.get("config-merge-arrays")
+            .and_then(|v| v.clone().try_into::<ArrayMerge>().ok())
+            .unwrap_or_default();
+        (merge_depth, merge_arrays)
+    }
+
+    /// Parses `file` as TOML and resolves its `include = [...]` directive,
+    /// if any (see [`helix_loader::resolve_includes`] for the details,
+    /// including the cycle/depth/absolute-path guards, shared with the
+    /// `languages.toml` loader in `helix-loader`). `file` itself is still
+    /// allowed to be missing, so a missing global/workspace config file
+    /// keeps falling back to defaults as before.
+    fn load_toml(file: &Path) -> Result<toml::Value, ConfigLoadError> {
+        if !file.exists() {
+            return Err(ConfigLoadError::Error(IOError::new(
+                std::io::ErrorKind::NotFound,
+                format!("{} does not exist", file.display()),
+            )));
+        }
+        let mut visited = HashSet::new();
+        helix_loader::resolve_includes(file, &mut visited, 0, &Self::merge_settings)
+            .map_err(ConfigLoadError::BadConfig)
     }
 
+    /// Like [`ConfigRaw::load`] layered with the workspace config, but
+    /// stops at the raw TOML level instead of converting to a typed
+    /// `ConfigRaw`. Used by [`Config::load_lenient`] so a malformed
+    /// section can be reported and defaulted rather than discarding the
+    /// rest of the document. The returned [`RawLayers`] keeps enough
+    /// provenance that a later per-section conversion failure can still
+    /// name the file that section actually came from.
+    fn load_raw() -> Result<RawLayers, ConfigLoadError> {
+        let global_path = helix_loader::config_file();
+        let global = Self::load_toml(&global_path)?;
+        let (merge_depth, merge_arrays) = Self::merge_settings(&global);
+
+        if !global.get("workspace-config").and_then(toml::Value::as_bool).unwrap_or_default() {
+            return Ok(RawLayers::global_only(global, global_path));
+        }
+
+        let workspace_path = helix_loader::workspace_config_file();
+        match Self::load_toml(&workspace_path) {
+            Ok(mut workspace) => {
+                // Workspace configs are untrusted and may not toggle `workspace-config` itself.
+                if let Some(table) = workspace.as_table_mut() {
+                    table.remove("workspace-config");
+                    if !helix_loader::is_workspace_trusted(&helix_loader::find_workspace()) {
+                        if let Some(editor) = table.remove("editor") {
+                            table.insert("editor".to_string(), filter_untrusted_editor(editor));
+                        }
+                    }
+                }
+                let workspace_keys = workspace
+                    .as_table()
+                    .map(|table| table.keys().cloned().collect())
+                    .unwrap_or_default();
+                let value = merge_toml_values(global, workspace, merge_depth, merge_arrays);
+                Ok(RawLayers {
+                    value,
+                    global_path,
+                    workspace_path: Some(workspace_path),
+                    workspace_keys,
+                })
+            }
+            Err(ConfigLoadError::Error(_)) => Ok(RawLayers::global_only(global, global_path)),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Merges `other` on top of `self`. `trust` marks whether `other` comes
+    /// from a trusted layer (the global config, or a workspace config the
+    /// user has explicitly trusted): an untrusted `other` may not set
+    /// [`UNTRUSTED_EDITOR_DENYLIST`] keys, since those control what
+    /// external commands Helix will run, nor `config_merge_depth`/
+    /// `config_merge_arrays` — an untrusted layer that controlled the merge
+    /// depth could force `merge_toml_values` to replace `self.editor`
+    /// outright (depth 0), wiping out trusted settings the denylist
+    /// filtering above is supposed to protect.
     fn merge(self, other: ConfigRaw, trust: bool) -> Self {
+        let (other_merge_depth, other_merge_arrays) = match trust {
+            true => (other.config_merge_depth, other.config_merge_arrays),
+            false => (None, None),
+        };
+        let merge_depth = other_merge_depth
+            .or(self.config_merge_depth)
+            .unwrap_or(DEFAULT_CONFIG_MERGE_DEPTH);
+        let merge_arrays = other_merge_arrays
+            .or(self.config_merge_arrays)
+            .unwrap_or_default();
+        let other_editor = if trust {
+            other.editor
+        } else {
+            other.editor.map(filter_untrusted_editor)
+        };
+
         ConfigRaw {
             workspace_config: match trust {
                 true =>  other.workspace_config.or(self.workspace_config),
@@ -62,10 +232,12 @@ impl ConfigRaw {
                 (Some(a), Some(b)) => Some(merge_keys(a, b)),
                 (opt_a, opt_b) => opt_a.or(opt_b),
             },
-            editor: match (self.editor, other.editor) {
-                (Some(a), Some(b)) => Some(merge_toml_values(a, b, 3)),
+            editor: match (self.editor, other_editor) {
+                (Some(a), Some(b)) => Some(merge_toml_values(a, b, merge_depth, merge_arrays)),
                 (opt_a, opt_b) => opt_a.or(opt_b),
-            }
+            },
+            config_merge_depth: other_merge_depth.or(self.config_merge_depth),
+            config_merge_arrays: other_merge_arrays.or(self.config_merge_arrays),
         }
     }
 }
@@ -100,17 +272,28 @@ impl Display for ConfigLoadError {
     }
 }
 
+/// Placeholder path used to label a `ConfigParseError` raised after
+/// several config layers have already been merged into one `toml::Value`,
+/// where there's no longer a single originating file to point at.
+const MERGED_CONFIG_PATH: &str = "<merged config>";
+
 impl TryFrom<ConfigRaw> for Config {
     type Error = ConfigLoadError;
 
     fn try_from(config: ConfigRaw) -> Result<Self, Self::Error> {
         Ok(Self {
             workspace_config: config.workspace_config.unwrap_or_default(),
-            theme: config.theme,
+            theme: config.theme.map(|theme| expand_string(&theme)),
             keys: config.keys.unwrap_or_else(|| keymap::default()),
             editor: config.editor
+                .map(expand_config_value)
                 .map(|e| e.try_into()).transpose()
-                .map_err(ConfigLoadError::BadConfig)?
+                .map_err(|error| {
+                    ConfigLoadError::BadConfig(ConfigParseError::without_source(
+                        PathBuf::from(MERGED_CONFIG_PATH),
+                        error,
+                    ))
+                })?
                 .unwrap_or_default(),
         })
     }
@@ -130,6 +313,139 @@ impl Config {
             }?,
        }.try_into()
     }
+
+    /// Like [`Config::load`], but lets a workspace config earn full trust
+    /// instead of always being restricted: a workspace that isn't yet in
+    /// the trust store (see `helix_loader::is_workspace_trusted`) is passed
+    /// to `prompt`, and a positive decision is persisted so future loads of
+    /// this workspace don't ask again; a denial is not recorded, so `prompt`
+    /// is called again on the next load. Denied or unprompted workspaces
+    /// still load, but with [`UNTRUSTED_EDITOR_DENYLIST`] keys stripped.
+    pub fn load_trusted(prompt: impl FnOnce(&Path) -> bool) -> Result<Config, ConfigLoadError> {
+        let default = ConfigRaw::default();
+        let global = default.merge(ConfigRaw::load(helix_loader::config_file())?, true);
+
+        if !global.workspace_config.unwrap_or_default() {
+            return global.try_into();
+        }
+
+        match ConfigRaw::load(helix_loader::workspace_config_file()) {
+            Ok(workspace) => {
+                let dir = helix_loader::find_workspace();
+                let trusted = helix_loader::is_workspace_trusted(&dir) || {
+                    let trusted = prompt(&dir);
+                    if trusted {
+                        let _ = helix_loader::trust_workspace(&dir);
+                    }
+                    trusted
+                };
+                global.merge(workspace, trusted).try_into()
+            }
+            Err(ConfigLoadError::Error(_)) => global.try_into(),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Like [`Config::load`], but never drops the whole config over a
+    /// single bad section: `theme`, `keys` and `editor` are each converted
+    /// independently, falling back to their default on failure. The
+    /// returned [`ConfigDiagnostics`] lists what was defaulted and why,
+    /// naming the global or workspace config file the bad section came
+    /// from (line/column aren't available here, since the section has
+    /// already been merged from its raw `toml::Value`, not reparsed from
+    /// source text), so the editor can surface it as startup warnings
+    /// instead of aborting.
+    pub fn load_lenient() -> (Config, ConfigDiagnostics) {
+        let mut diagnostics = ConfigDiagnostics::default();
+
+        let raw = match ConfigRaw::load_raw() {
+            Ok(raw) => raw,
+            Err(ConfigLoadError::Error(_)) => {
+                RawLayers::global_only(toml::Value::Table(Default::default()), helix_loader::config_file())
+            }
+            Err(ConfigLoadError::BadConfig(error)) => {
+                diagnostics.push("config", error);
+                RawLayers::global_only(toml::Value::Table(Default::default()), helix_loader::config_file())
+            }
+        };
+
+        let workspace_config = raw
+            .value
+            .get("workspace-config")
+            .and_then(toml::Value::as_bool)
+            .unwrap_or_default();
+
+        let theme = match raw.value.get("theme").cloned() {
+            Some(value) => match value.try_into::<String>() {
+                Ok(theme) => Some(expand_string(&theme)),
+                Err(error) => {
+                    diagnostics.push("theme", ConfigParseError::without_source(raw.path_for("theme"), error));
+                    None
+                }
+            },
+            None => None,
+        };
+
+        let keys = match raw.value.get("keys").cloned() {
+            Some(value) => match value.try_into() {
+                Ok(keys) => merge_keys(keymap::default(), keys),
+                Err(error) => {
+                    diagnostics.push("keys", ConfigParseError::without_source(raw.path_for("keys"), error));
+                    keymap::default()
+                }
+            },
+            None => keymap::default(),
+        };
+
+        let editor = match raw.value.get("editor").cloned() {
+            Some(value) => match expand_config_value(value).try_into() {
+                Ok(editor) => editor,
+                Err(error) => {
+                    diagnostics.push("editor", ConfigParseError::without_source(raw.path_for("editor"), error));
+                    helix_view::editor::Config::default()
+                }
+            },
+            None => helix_view::editor::Config::default(),
+        };
+
+        (
+            Config {
+                workspace_config,
+                theme,
+                keys,
+                editor,
+            },
+            diagnostics,
+        )
+    }
+}
+
+/// One config section that failed to load and was replaced with its
+/// default, as reported by [`Config::load_lenient`]. `error` names the
+/// offending file and, where available, its 1-based line/column.
+#[derive(Debug)]
+pub struct ConfigDiagnostic {
+    pub section: &'static str,
+    pub error: ConfigParseError,
+}
+
+impl Display for ConfigDiagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {} (using default)", self.section, self.error)
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct ConfigDiagnostics(pub Vec<ConfigDiagnostic>);
+
+impl ConfigDiagnostics {
+    fn push(&mut self, section: &'static str, error: ConfigParseError) {
+        self.0.push(ConfigDiagnostic { section, error });
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
 }
 
 #[cfg(test)]
@@ -190,4 +506,55 @@ mod tests {
         let default_keys = Config::default().keys;
         assert_eq!(default_keys, keymap::default());
     }
+
+    #[test]
+    fn merge_ignores_untrusted_config_merge_depth() {
+        let base: ConfigRaw = toml::from_str(
+            r#"
+            [editor]
+            auto-save = true
+            "#,
+        )
+        .unwrap();
+
+        // An untrusted layer setting `config-merge-depth = 0` must not be
+        // able to force its `editor` table to replace the trusted one
+        // outright: `auto-save` belongs to the base layer and isn't
+        // redeclared here, so it must survive the merge.
+        let untrusted: ConfigRaw = toml::from_str(
+            r#"
+            config-merge-depth = 0
+
+            [editor]
+            line-number = "relative"
+            "#,
+        )
+        .unwrap();
+
+        let merged = base.merge(untrusted, false);
+        let editor = merged.editor.unwrap();
+        assert_eq!(editor.get("auto-save").and_then(toml::Value::as_bool), Some(true));
+        assert_eq!(
+            editor.get("line-number").and_then(toml::Value::as_str),
+            Some("relative")
+        );
+    }
+
+    #[test]
+    fn load_toml_detects_include_cycles() {
+        let dir = std::env::temp_dir().join(format!(
+            "helix-term-test-include-cycle-{}",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("a.toml"), "include = [\"b.toml\"]\n").unwrap();
+        std::fs::write(dir.join("b.toml"), "include = [\"a.toml\"]\n").unwrap();
+
+        let error = ConfigRaw::load_toml(&dir.join("a.toml"))
+            .expect_err("an include cycle should error instead of overflowing the stack");
+        assert!(matches!(error, ConfigLoadError::BadConfig(_)));
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
 }